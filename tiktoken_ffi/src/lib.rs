@@ -1,8 +1,82 @@
 use tiktoken_rs::{get_bpe_from_model, CoreBPE};
+use rayon::prelude::*;
 use std::ffi::CStr;
 use std::sync::Mutex;
 
-static TOKENIZER: Mutex<Option<CoreBPE>> = Mutex::new(None);
+/// An opaque, owned tokenizer instance. `CoreBPE` is immutable once built,
+/// so a handle can be shared across threads without a lock.
+pub struct TokenizerHandle {
+    bpe: CoreBPE,
+}
+
+unsafe impl Send for TokenizerHandle {}
+unsafe impl Sync for TokenizerHandle {}
+
+static DEFAULT_HANDLE: Mutex<Option<Box<TokenizerHandle>>> = Mutex::new(None);
+
+/// Serializes tests that drive the shared `DEFAULT_HANDLE` global through
+/// `tiktoken_init`/`tiktoken_cleanup`, since cargo runs tests concurrently.
+#[cfg(test)]
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+fn bpe_for_encoding(encoding_str: &str) -> Option<CoreBPE> {
+    match encoding_str {
+        "o200k_base" => get_bpe_from_model("gpt-4o").ok(),
+        "cl100k_base" => get_bpe_from_model("gpt-3.5-turbo").ok(),
+        "p50k_base" => get_bpe_from_model("text-davinci-003").ok(),
+        "p50k_edit" => get_bpe_from_model("text-davinci-edit-001").ok(),
+        "r50k_base" => get_bpe_from_model("gpt2").ok(),
+        _ => None,
+    }
+}
+
+/// Creates a standalone tokenizer handle for `encoding`, or null if the
+/// encoding name is unknown. Caller releases it with `tiktoken_free`.
+#[no_mangle]
+pub extern "C" fn tiktoken_new(encoding: *const u8) -> *mut TokenizerHandle {
+    let encoding_str = unsafe {
+        if encoding.is_null() {
+            return std::ptr::null_mut();
+        }
+        match CStr::from_ptr(encoding as *const i8).to_str() {
+            Ok(s) => s,
+            Err(_) => return std::ptr::null_mut(),
+        }
+    };
+
+    match bpe_for_encoding(encoding_str) {
+        Some(bpe) => Box::into_raw(Box::new(TokenizerHandle { bpe })),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Counts tokens in `text` using a specific handle.
+///
+/// # Safety
+/// `handle` must be a pointer previously returned by `tiktoken_new` and
+/// not yet freed; `text` must be null or a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_count_h(handle: *mut TokenizerHandle, text: *const u8) -> usize {
+    if handle.is_null() || text.is_null() {
+        return 0;
+    }
+
+    let handle = &*handle;
+    let text_str = CStr::from_ptr(text as *const i8).to_str().unwrap_or("");
+    handle.bpe.encode_with_special_tokens(text_str).len()
+}
+
+/// Releases a handle created by `tiktoken_new`.
+///
+/// # Safety
+/// `handle` must be a pointer previously returned by `tiktoken_new`, not
+/// already freed.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_free(handle: *mut TokenizerHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
 
 #[no_mangle]
 pub extern "C" fn tiktoken_init(encoding: *const u8) -> i32 {
@@ -13,24 +87,17 @@ pub extern "C" fn tiktoken_init(encoding: *const u8) -> i32 {
         CStr::from_ptr(encoding as *const i8).to_str().unwrap_or("cl100k_base")
     };
 
-    let bpe = match encoding_str {
-        "o200k_base" => get_bpe_from_model("gpt-4o").ok(),
-        "cl100k_base" => get_bpe_from_model("gpt-3.5-turbo").ok(),
-        "p50k_base" => get_bpe_from_model("text-davinci-003").ok(),
-        "p50k_edit" => get_bpe_from_model("text-davinci-edit-001").ok(),
-        "r50k_base" => get_bpe_from_model("gpt2").ok(),
-        _ => return -2,
+    let bpe = match bpe_for_encoding(encoding_str) {
+        Some(bpe) => bpe,
+        None => return -2,
     };
 
-    match TOKENIZER.lock() {
-        Ok(mut tokenizer) => {
-            *tokenizer = bpe;
-            if tokenizer.is_none() {
-                return -4;
-            }
+    match DEFAULT_HANDLE.lock() {
+        Ok(mut handle) => {
+            *handle = Some(Box::new(TokenizerHandle { bpe }));
             0
         }
-        Err(_) => -3, 
+        Err(_) => -3,
     }
 }
 
@@ -43,18 +110,511 @@ pub extern "C" fn tiktoken_count(text: *const u8) -> usize {
         CStr::from_ptr(text as *const i8).to_str().unwrap_or("")
     };
 
-    match TOKENIZER.lock() {
-        Ok(tokenizer) => match *tokenizer {
-            Some(ref bpe) => bpe.encode_with_special_tokens(text_str).len(),
-            None => 0, 
+    match DEFAULT_HANDLE.lock() {
+        Ok(handle) => match *handle {
+            Some(ref h) => h.bpe.encode_with_special_tokens(text_str).len(),
+            None => 0,
         },
-        Err(_) => 0, 
+        Err(_) => 0,
     }
 }
 
 #[no_mangle]
 pub extern "C" fn tiktoken_cleanup() {
-    if let Ok(mut tokenizer) = TOKENIZER.lock() {
-        *tokenizer = None;
+    if let Ok(mut handle) = DEFAULT_HANDLE.lock() {
+        *handle = None;
+    }
+}
+
+/// Writes byte offsets where `text` should be cut so every chunk encodes
+/// to at most `max_tokens` tokens. Offsets always fall on token
+/// boundaries (the last one equal to `text`'s byte length). Returns 0 on
+/// success, or a negative error code.
+///
+/// # Safety
+/// `text`, `out_offsets` and `out_len` must be valid for their documented
+/// uses; `out_offsets` is only dereferenced after the null checks below.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_split_chunks(
+    text: *const u8,
+    max_tokens: usize,
+    out_offsets: *mut *mut usize,
+    out_len: *mut usize,
+) -> i32 {
+    if text.is_null() || out_offsets.is_null() || out_len.is_null() {
+        return -1;
+    }
+
+    let text_str = CStr::from_ptr(text as *const i8).to_str().unwrap_or("");
+
+    let handle = match DEFAULT_HANDLE.lock() {
+        Ok(handle) => handle,
+        Err(_) => return -3,
+    };
+    let bpe = match handle.as_ref() {
+        Some(h) => &h.bpe,
+        None => return -2,
+    };
+
+    if max_tokens == 0 || text_str.is_empty() {
+        *out_offsets = std::ptr::null_mut();
+        *out_len = 0;
+        return 0;
+    }
+
+    let tokens = bpe.encode_with_special_tokens(text_str);
+    let mut offsets = Vec::new();
+    let mut run = 0usize;
+    let mut byte_len = 0usize;
+
+    for token in tokens {
+        if run == max_tokens {
+            offsets.push(byte_len);
+            run = 0;
+        }
+        // A single token's bytes aren't always valid UTF-8 on their own, so
+        // go through the raw decoder instead of `decode` to get its true
+        // byte length.
+        byte_len += bpe._decode_native(&[token]).len();
+        run += 1;
+    }
+    offsets.push(byte_len);
+
+    let mut boxed = offsets.into_boxed_slice();
+    *out_len = boxed.len();
+    *out_offsets = boxed.as_mut_ptr();
+    std::mem::forget(boxed);
+    0
+}
+
+/// Releases an offsets array produced by `tiktoken_split_chunks` or
+/// `tiktoken_decode_pieces`.
+///
+/// # Safety
+/// `offsets` must be a pointer previously returned by one of those
+/// functions, with the matching `len`.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_free_offsets(offsets: *mut usize, len: usize) {
+    if !offsets.is_null() {
+        drop(Vec::from_raw_parts(offsets, len, len));
+    }
+}
+
+#[cfg(test)]
+mod split_chunks_tests {
+    use super::*;
+
+    #[test]
+    fn boundaries_land_on_whole_tokens_for_multibyte_text() {
+        let _guard = TEST_LOCK.lock().unwrap();
+        assert_eq!(tiktoken_init(b"cl100k_base\0".as_ptr()), 0);
+
+        let text = "Hello \u{4e16}\u{754c}, this has \u{e9}moji \u{1f389} and accents caf\u{e9}.";
+        let c_text = std::ffi::CString::new(text).unwrap();
+
+        let mut out_offsets: *mut usize = std::ptr::null_mut();
+        let mut out_len: usize = 0;
+        let rc = unsafe {
+            tiktoken_split_chunks(c_text.as_ptr() as *const u8, 1, &mut out_offsets, &mut out_len)
+        };
+        assert_eq!(rc, 0);
+
+        let offsets = unsafe { std::slice::from_raw_parts(out_offsets, out_len) };
+        assert_eq!(*offsets.last().unwrap(), text.len());
+
+        unsafe { tiktoken_free_offsets(out_offsets, out_len) };
+        tiktoken_cleanup();
+    }
+}
+
+/// Encodes `text` with the default tokenizer, writing the raw token IDs to
+/// `*out_tokens`/`*out_len`. Caller releases with `tiktoken_free_tokens`.
+///
+/// # Safety
+/// `text`, `out_tokens` and `out_len` must be valid for their documented
+/// uses; the out-params are only dereferenced after the null checks below.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_encode(
+    text: *const u8,
+    out_tokens: *mut *mut u32,
+    out_len: *mut usize,
+) -> i32 {
+    if text.is_null() || out_tokens.is_null() || out_len.is_null() {
+        return -1;
+    }
+
+    let text_str = CStr::from_ptr(text as *const i8).to_str().unwrap_or("");
+
+    let handle = match DEFAULT_HANDLE.lock() {
+        Ok(handle) => handle,
+        Err(_) => return -3,
+    };
+    let bpe = match handle.as_ref() {
+        Some(h) => &h.bpe,
+        None => return -2,
+    };
+
+    let tokens: Vec<u32> = bpe
+        .encode_with_special_tokens(text_str)
+        .into_iter()
+        .map(|t| t as u32)
+        .collect();
+
+    let mut boxed = tokens.into_boxed_slice();
+    *out_len = boxed.len();
+    *out_tokens = boxed.as_mut_ptr();
+    std::mem::forget(boxed);
+    0
+}
+
+/// A token ID outside the tokenizer's vocabulary: `CoreBPE`'s native
+/// decoder indexes its decode maps directly and panics on a miss, so this
+/// boundary runs it behind `catch_unwind` rather than let a bad ID from
+/// the C side abort the process.
+const ERR_INVALID_TOKEN: i32 = -4;
+
+/// Decodes `tokens` back to raw bytes, writing them to
+/// `*out_text`/`*out_len`. Caller releases with `tiktoken_free_string`.
+///
+/// # Safety
+/// `tokens` must be valid for `len` elements; `out_text`/`out_len` must be
+/// valid for their documented uses.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_decode(
+    tokens: *const u32,
+    len: usize,
+    out_text: *mut *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    if tokens.is_null() || out_text.is_null() || out_len.is_null() {
+        return -1;
+    }
+
+    let handle = match DEFAULT_HANDLE.lock() {
+        Ok(handle) => handle,
+        Err(_) => return -3,
+    };
+    let bpe = match handle.as_ref() {
+        Some(h) => &h.bpe,
+        None => return -2,
+    };
+
+    let token_vec: Vec<usize> = std::slice::from_raw_parts(tokens, len)
+        .iter()
+        .map(|&t| t as usize)
+        .collect();
+
+    let bytes = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        bpe._decode_native(&token_vec)
+    })) {
+        Ok(bytes) => bytes,
+        Err(_) => return ERR_INVALID_TOKEN,
+    };
+
+    let mut boxed = bytes.into_boxed_slice();
+    *out_len = boxed.len();
+    *out_text = boxed.as_mut_ptr();
+    std::mem::forget(boxed);
+    0
+}
+
+/// Decodes each token in `tokens` individually, writing every token's raw
+/// bytes back-to-back into `*out_data` and the cumulative byte offset
+/// after each token into `*out_offsets` (so token `i` spans
+/// `out_offsets[i - 1]..out_offsets[i]`), letting a caller map token
+/// positions back to byte ranges. Caller releases `*out_data` with
+/// `tiktoken_free_string` and `*out_offsets` with `tiktoken_free_offsets`.
+///
+/// # Safety
+/// `tokens` must be valid for `len` elements; the out-params must be
+/// valid for their documented uses.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_decode_pieces(
+    tokens: *const u32,
+    len: usize,
+    out_data: *mut *mut u8,
+    out_data_len: *mut usize,
+    out_offsets: *mut *mut usize,
+    out_count: *mut usize,
+) -> i32 {
+    if tokens.is_null() || out_data.is_null() || out_data_len.is_null() || out_offsets.is_null() || out_count.is_null() {
+        return -1;
+    }
+
+    let handle = match DEFAULT_HANDLE.lock() {
+        Ok(handle) => handle,
+        Err(_) => return -3,
+    };
+    let bpe = match handle.as_ref() {
+        Some(h) => &h.bpe,
+        None => return -2,
+    };
+
+    let token_ids: Vec<usize> = std::slice::from_raw_parts(tokens, len)
+        .iter()
+        .map(|&t| t as usize)
+        .collect();
+
+    // `_decode_native_and_split` is lazy, so the panicking lookups only run
+    // once the iterator is driven; collect it *inside* `catch_unwind` so
+    // that work happens under the guard instead of after it returns.
+    let pieces: Vec<Vec<u8>> = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        bpe._decode_native_and_split(token_ids).collect()
+    })) {
+        Ok(pieces) => pieces,
+        Err(_) => return ERR_INVALID_TOKEN,
+    };
+
+    let mut data = Vec::new();
+    let mut offsets = Vec::with_capacity(pieces.len());
+    for piece in pieces {
+        data.extend_from_slice(&piece);
+        offsets.push(data.len());
+    }
+
+    let mut data_boxed = data.into_boxed_slice();
+    let mut offsets_boxed = offsets.into_boxed_slice();
+    *out_data_len = data_boxed.len();
+    *out_data = data_boxed.as_mut_ptr();
+    *out_count = offsets_boxed.len();
+    *out_offsets = offsets_boxed.as_mut_ptr();
+    std::mem::forget(data_boxed);
+    std::mem::forget(offsets_boxed);
+    0
+}
+
+/// Releases a token array produced by `tiktoken_encode`.
+///
+/// # Safety
+/// `tokens` must be a pointer previously returned by `tiktoken_encode`,
+/// with the matching `len`.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_free_tokens(tokens: *mut u32, len: usize) {
+    if !tokens.is_null() {
+        drop(Vec::from_raw_parts(tokens, len, len));
+    }
+}
+
+/// Releases a byte buffer produced by `tiktoken_decode` or
+/// `tiktoken_decode_pieces`.
+///
+/// # Safety
+/// `text` must be a pointer previously returned by one of those
+/// functions, with the matching `len`.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_free_string(text: *mut u8, len: usize) {
+    if !text.is_null() {
+        drop(Vec::from_raw_parts(text, len, len));
+    }
+}
+
+#[cfg(test)]
+mod decode_pieces_tests {
+    use super::*;
+
+    #[test]
+    fn pieces_cover_every_byte_for_multibyte_text() {
+        let _guard = TEST_LOCK.lock().unwrap();
+        assert_eq!(tiktoken_init(b"cl100k_base\0".as_ptr()), 0);
+
+        let text = "Hello \u{4e16}\u{754c}, this has \u{e9}moji \u{1f389} and accents caf\u{e9}.";
+        let c_text = std::ffi::CString::new(text).unwrap();
+
+        let mut tokens: *mut u32 = std::ptr::null_mut();
+        let mut tokens_len: usize = 0;
+        assert_eq!(
+            unsafe { tiktoken_encode(c_text.as_ptr() as *const u8, &mut tokens, &mut tokens_len) },
+            0
+        );
+
+        let mut out_data: *mut u8 = std::ptr::null_mut();
+        let mut out_data_len: usize = 0;
+        let mut out_offsets: *mut usize = std::ptr::null_mut();
+        let mut out_count: usize = 0;
+        let rc = unsafe {
+            tiktoken_decode_pieces(
+                tokens,
+                tokens_len,
+                &mut out_data,
+                &mut out_data_len,
+                &mut out_offsets,
+                &mut out_count,
+            )
+        };
+        assert_eq!(rc, 0);
+        assert_eq!(out_data_len, text.len());
+
+        let reconstructed = unsafe { std::slice::from_raw_parts(out_data, out_data_len) };
+        assert_eq!(reconstructed, text.as_bytes());
+
+        unsafe {
+            tiktoken_free_tokens(tokens, tokens_len);
+            tiktoken_free_string(out_data, out_data_len);
+            tiktoken_free_offsets(out_offsets, out_count);
+        }
+        tiktoken_cleanup();
+    }
+}
+
+/// Bare (non-dated) model names where `tiktoken_rs::model::get_context_size`
+/// resolves to the wrong size: it matches `"gpt-4"`'s generic 8192 branch
+/// for these before any turbo/o-series-specific case, only getting dated
+/// variants like `"gpt-4-0125-preview"` right. Checked before delegating.
+const CONTEXT_SIZE_OVERRIDES: &[(&str, usize)] = &[
+    ("gpt-4-turbo", 128000),
+    ("gpt-4-turbo-preview", 128000),
+    ("gpt-4o", 128000),
+    ("gpt-4o-mini", 128000),
+];
+
+/// Returns the context-window size in tokens for known OpenAI models, or
+/// 0 if `model` isn't recognized. Delegates to `tiktoken_rs::model` for
+/// everything but `CONTEXT_SIZE_OVERRIDES`, rather than hand-maintaining a
+/// full second table that would drift from it; `get_context_size` on its
+/// own falls back to a generic 4096 for an unrecognized model, so the
+/// recognition check is done separately via `get_bpe_from_model`.
+fn model_context_size(model: &str) -> usize {
+    if let Some(&(_, size)) = CONTEXT_SIZE_OVERRIDES.iter().find(|&&(name, _)| name == model) {
+        return size;
+    }
+    if get_bpe_from_model(model).is_err() {
+        return 0;
+    }
+    tiktoken_rs::model::get_context_size(model)
+}
+
+#[cfg(test)]
+mod model_context_size_tests {
+    use super::*;
+
+    #[test]
+    fn turbo_and_o_series_get_their_full_128k_window() {
+        assert_eq!(model_context_size("gpt-4-turbo"), 128000);
+        assert_eq!(model_context_size("gpt-4o"), 128000);
+        assert_eq!(model_context_size("gpt-4o-mini"), 128000);
+    }
+}
+
+/// Looks up the context-window size for `model` in tokens, or 0 if the
+/// model name isn't recognized.
+#[no_mangle]
+pub extern "C" fn tiktoken_model_context_size(model: *const u8) -> usize {
+    let model_str = unsafe {
+        if model.is_null() {
+            return 0;
+        }
+        CStr::from_ptr(model as *const i8).to_str().unwrap_or("")
+    };
+
+    model_context_size(model_str)
+}
+
+/// Counts tokens in `text` using `model`'s own encoding and returns how
+/// much of its context window is left: `context_size - answer_headroom -
+/// used`. Reserve room for a completion by passing a non-zero
+/// `answer_headroom`. The result is negative once the dump (plus the
+/// reserved headroom) would overflow the model's context window. An
+/// unrecognized model or a null argument returns `isize::MIN` without
+/// counting anything.
+#[no_mangle]
+pub extern "C" fn tiktoken_remaining_tokens(
+    model: *const u8,
+    text: *const u8,
+    answer_headroom: usize,
+) -> isize {
+    let (model_str, text_str) = unsafe {
+        if model.is_null() || text.is_null() {
+            return isize::MIN;
+        }
+        (
+            CStr::from_ptr(model as *const i8).to_str().unwrap_or(""),
+            CStr::from_ptr(text as *const i8).to_str().unwrap_or(""),
+        )
+    };
+
+    let bpe = match get_bpe_from_model(model_str) {
+        Ok(bpe) => bpe,
+        Err(_) => return isize::MIN,
+    };
+
+    let used = bpe.encode_with_special_tokens(text_str).len();
+    model_context_size(model_str) as isize - answer_headroom as isize - used as isize
+}
+
+/// Counts tokens for `count` C strings in `texts` in a single call,
+/// writing each string's count to the matching slot in `out_counts` and
+/// returning the grand total. Parallelized across `texts` with rayon; each
+/// worker shares the default tokenizer by reference.
+///
+/// # Safety
+/// `texts` must be valid for `count` pointers, each either null or a valid
+/// C string; `out_counts` must be valid for `count` elements.
+#[no_mangle]
+pub unsafe extern "C" fn tiktoken_count_batch(
+    texts: *const *const u8,
+    count: usize,
+    out_counts: *mut usize,
+) -> usize {
+    if texts.is_null() || out_counts.is_null() || count == 0 {
+        return 0;
+    }
+
+    let handle = match DEFAULT_HANDLE.lock() {
+        Ok(handle) => handle,
+        Err(_) => return 0,
+    };
+    let bpe = match handle.as_ref() {
+        Some(h) => &h.bpe,
+        None => return 0,
+    };
+
+    let counts = std::slice::from_raw_parts_mut(out_counts, count);
+    // `*const *const u8` isn't `Send`, so thread it through the parallel
+    // closures as a plain address and re-derive each element's pointer by
+    // index instead of handing rayon a slice of raw pointers directly.
+    let texts_addr = texts as usize;
+
+    counts.par_iter_mut().enumerate().for_each(|(i, count_out)| {
+        let ptr = unsafe { *(texts_addr as *const *const u8).add(i) };
+        let text_str = if ptr.is_null() {
+            ""
+        } else {
+            unsafe { CStr::from_ptr(ptr as *const i8).to_str().unwrap_or("") }
+        };
+        *count_out = bpe.encode_with_special_tokens(text_str).len();
+    });
+
+    counts.iter().sum()
+}
+
+/// Initializes the default tokenizer from a model name (e.g. `"gpt-4o"`,
+/// `"text-embedding-3-small"`) rather than a raw encoding name, resolving
+/// it via `get_bpe_from_model`. This intentionally deviates from a
+/// one-row-per-model lookup table: it reuses `get_bpe_from_model`'s own
+/// model list (including fine-tune and Azure naming) instead of a second,
+/// hand-maintained one, at the cost of a new model being a `tiktoken-rs`
+/// version bump rather than a one-row change here. Returns 0 on success,
+/// -5 if `model` isn't recognized, or -3 if the default-handle lock is
+/// poisoned.
+#[no_mangle]
+pub extern "C" fn tiktoken_init_for_model(model: *const u8) -> i32 {
+    let model_str = unsafe {
+        if model.is_null() {
+            return -1;
+        }
+        CStr::from_ptr(model as *const i8).to_str().unwrap_or("")
+    };
+
+    let bpe = match get_bpe_from_model(model_str) {
+        Ok(bpe) => bpe,
+        Err(_) => return -5,
+    };
+
+    match DEFAULT_HANDLE.lock() {
+        Ok(mut handle) => {
+            *handle = Some(Box::new(TokenizerHandle { bpe }));
+            0
+        }
+        Err(_) => -3,
     }
 }